@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use deadpool_redis::Pool as RedisPool;
+use redis::AsyncCommands;
+use serde_json::to_string;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+
+use crate::Pessoa;
+
+const CHANNEL: &str = "pessoas_changed";
+const MIN_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Keeps the Redis cache correct regardless of who mutates `pessoas` (bulk
+/// loads, manual SQL, future update endpoints) by listening for the
+/// `pessoas_changed` notifications a Postgres trigger emits on every
+/// INSERT/UPDATE/DELETE, then re-syncing just that row.
+pub async fn spawn_cache_listener(pool: PgPool, redis_pool: RedisPool, database_url: &str) {
+    let mut listener = match PgListener::connect(database_url).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to start pessoas_changed listener: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = listener.listen(CHANNEL).await {
+        println!("Failed to LISTEN on {}: {}", CHANNEL, e);
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut backoff = MIN_RETRY_BACKOFF;
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => {
+                    backoff = MIN_RETRY_BACKOFF;
+                    notification
+                }
+                Err(e) => {
+                    println!(
+                        "pessoas_changed listener error, retrying in {:?}: {}",
+                        backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                    continue;
+                }
+            };
+
+            if let Err(e) = sync_one(&pool, &redis_pool, notification.payload()).await {
+                println!("Failed to sync cache for {}: {}", notification.payload(), e);
+            }
+        }
+    });
+}
+
+async fn sync_one(
+    pool: &PgPool,
+    redis_pool: &RedisPool,
+    id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pessoa = sqlx::query_as::<_, Pessoa>(
+        "SELECT id, apelido, nome, nascimento, stack FROM pessoas WHERE id = $1::uuid",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    let mut redis = redis_pool.get().await?;
+    match pessoa {
+        Some(pessoa) => {
+            redis.set::<_, _, ()>(id, to_string(&pessoa)?).await?;
+        }
+        None => {
+            redis.del::<_, ()>(id).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::env;
+use std::error::Error;
+
+use async_trait::async_trait;
+
+/// Produces a fixed-size float vector for a piece of text so it can be
+/// compared with `pessoas.embedding` using pgvector's cosine distance.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error + Send + Sync>>;
+}
+
+/// Calls out to an HTTP embedding endpoint (e.g. a hosted model or a
+/// sidecar). This is the only backend for now; a local/in-process model
+/// can be added behind the same trait once one is actually wired up.
+pub struct HttpEmbedder {
+    endpoint: String,
+    client: reqwest::Client,
+}
+
+impl HttpEmbedder {
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            endpoint,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Embedder for HttpEmbedder {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error + Send + Sync>> {
+        let response: EmbeddingResponse = self
+            .client
+            .post(&self.endpoint)
+            .json(&EmbeddingRequest { input: text })
+            .send()
+            .await?
+            .json()
+            .await?;
+        Ok(response.embedding)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct EmbeddingRequest<'a> {
+    input: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+struct EmbeddingResponse {
+    embedding: Vec<f32>,
+}
+
+/// Builds the configured embedder, or `None` if `EMBEDDER_HTTP_URL` isn't
+/// set. Semantic search and embedding-on-write are opt-in, so a deployment
+/// that never passes `mode=semantic` shouldn't need this configured at all.
+pub fn build_embedder() -> Option<Box<dyn Embedder>> {
+    let url = env::var("EMBEDDER_HTTP_URL").ok()?;
+    Some(Box::new(HttpEmbedder::new(url)))
+}
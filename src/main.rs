@@ -3,7 +3,9 @@ use std::time::Duration;
 use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
 use chrono::NaiveDate;
 use deadpool_redis::{Config, Runtime};
-use redis::AsyncCommands;
+use once_cell::sync::Lazy;
+use pgvector::Vector;
+use redis::{AsyncCommands, Script};
 use serde::Deserialize;
 use serde_derive::Serialize;
 use serde_json::to_string;
@@ -12,8 +14,16 @@ use sqlx::{PgPool, Row};
 use uuid::Uuid;
 use validator::{Validate, ValidationError};
 
+mod cache_sync;
+mod embedder;
+mod metrics;
+mod ratelimit;
+
+use embedder::Embedder;
+use ratelimit::RateLimit;
+
 #[derive(Debug, Validate, Deserialize, sqlx::FromRow, Serialize)]
-struct Pessoa {
+pub(crate) struct Pessoa {
     id: Option<Uuid>,
 
     #[validate(required, length(min = 1, max = 32))]
@@ -28,6 +38,12 @@ struct Pessoa {
     #[serde(default)]
     #[validate(custom = "validate_stack")]
     stack: Option<Vec<String>>,
+
+    /// Only populated by the `mode=semantic` search, where it carries the
+    /// cosine similarity against the query embedding.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    similarity: Option<f64>,
 }
 
 fn validate_stack(stack: &[String]) -> Result<(), ValidationError> {
@@ -39,10 +55,33 @@ fn validate_stack(stack: &[String]) -> Result<(), ValidationError> {
     Ok(())
 }
 
+/// Atomically reserves the apelido and stores the person blob in one Redis
+/// round trip, so concurrent inserts of the same apelido can't both pass.
+/// Both keys carry a short TTL (`APELIDO_RESERVATION_TTL_SECS`) so a crash
+/// between this script and the Postgres insert that follows frees the
+/// apelido on its own; `CONFIRM_PESSOA_SCRIPT`/`ROLLBACK_PESSOA_SCRIPT`
+/// resolve that TTL once the insert's outcome is known.
+static CREATE_PESSOA_SCRIPT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("../scripts/create_pessoa.lua")));
+
+/// Drops the reservation TTL once the Postgres insert has succeeded.
+static CONFIRM_PESSOA_SCRIPT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("../scripts/confirm_pessoa.lua")));
+
+/// Compensates a failed Postgres insert by freeing the apelido reservation
+/// and cached blob that `CREATE_PESSOA_SCRIPT` put in place.
+static ROLLBACK_PESSOA_SCRIPT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("../scripts/rollback_pessoa.lua")));
+
+fn apelido_reservation_key(apelido: &str) -> String {
+    format!("apelido_reserved:{}", apelido)
+}
+
 #[post("/pessoas")]
 async fn create_pessoa(
     pool: web::Data<PgPool>,
     redis_pool: web::Data<deadpool_redis::Pool>,
+    embedder: web::Data<Option<Box<dyn Embedder>>>,
     mut pessoa: web::Json<Pessoa>,
 ) -> impl Responder {
     if let Err(errors) = pessoa.validate() {
@@ -53,18 +92,20 @@ async fn create_pessoa(
     pessoa.id = Some(id);
     let serialized_person = to_string(&pessoa).unwrap();
     let mut redis = redis_pool.get_ref().get().await.unwrap();
-    let exists = redis
-        .sadd::<_, _, i32>("apelidos", &pessoa.apelido)
+    let reservation_key = apelido_reservation_key(pessoa.apelido.as_deref().unwrap_or(""));
+    let reservation_ttl_secs: u64 = env_or("APELIDO_RESERVATION_TTL_SECS", 30);
+    let reserved: i32 = CREATE_PESSOA_SCRIPT
+        .key(&reservation_key)
+        .key(id.to_string())
+        .arg(&serialized_person)
+        .arg(reservation_ttl_secs)
+        .invoke_async(&mut redis)
         .await
         .unwrap();
-    if exists == 0 {
+    if reserved == 0 {
         return HttpResponse::UnprocessableEntity().finish();
     }
 
-    redis
-        .set::<_, _, ()>(id.to_string(), &serialized_person)
-        .await
-        .unwrap();
     let stack_str = match &pessoa.stack {
         Some(s) => s.join(" "),
         None => String::from(""),
@@ -76,9 +117,21 @@ async fn create_pessoa(
         stack_str
     )
     .to_lowercase();
+
+    let embedding = match embedder.get_ref() {
+        Some(embedder) => match embedder.embed(&search_text).await {
+            Ok(vector) => Some(Vector::from(vector)),
+            Err(e) => {
+                println!("Failed to compute embedding: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
     let result = sqlx::query(
-        "INSERT INTO pessoas (id, apelido, nome, nascimento, stack, search_vector) VALUES \
-        ($1, $2, $3, $4, $5, $6)",
+        "INSERT INTO pessoas (id, apelido, nome, nascimento, stack, search_vector, embedding) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7)",
     )
     .bind(id)
     .bind(&pessoa.apelido)
@@ -86,15 +139,37 @@ async fn create_pessoa(
     .bind(&pessoa.nascimento)
     .bind(&pessoa.stack)
     .bind(&search_text)
+    .bind(&embedding)
     .execute(pool.get_ref())
     .await;
 
     match result {
-        Ok(_) => HttpResponse::Created()
-            .append_header(("Location", format!("/pessoas/{}", id)))
-            .finish(),
+        Ok(_) => {
+            let confirm: Result<i32, _> = CONFIRM_PESSOA_SCRIPT
+                .key(&reservation_key)
+                .key(id.to_string())
+                .invoke_async(&mut redis)
+                .await;
+            if let Err(e) = confirm {
+                println!("Failed to confirm apelido reservation for {}: {}", id, e);
+            }
+            HttpResponse::Created()
+                .append_header(("Location", format!("/pessoas/{}", id)))
+                .finish()
+        }
         Err(e) => {
             println!("Failed to execute query: {}", e);
+            let rollback: Result<i32, _> = ROLLBACK_PESSOA_SCRIPT
+                .key(&reservation_key)
+                .key(id.to_string())
+                .invoke_async(&mut redis)
+                .await;
+            if let Err(e) = rollback {
+                println!(
+                    "Failed to roll back apelido reservation for {}: {}",
+                    id, e
+                );
+            }
             HttpResponse::InternalServerError().finish()
         }
     }
@@ -109,10 +184,12 @@ async fn get_pessoa_by_id(
     let mut redis = redis_pool.get_ref().get().await.unwrap();
     let pessoa_json: Option<String> = redis.get(id.clone().to_string()).await.unwrap_or(None);
     if let Some(json_data) = pessoa_json {
+        metrics::counter!("pessoa_cache_hits_total").increment(1);
         return Ok(HttpResponse::Ok()
             .content_type("application/json")
             .body(json_data));
     }
+    metrics::counter!("pessoa_cache_misses_total").increment(1);
 
     let result = sqlx::query_as::<_, Pessoa>(
         "SELECT id, apelido, nome, nascimento, stack FROM pessoas WHERE id = $1",
@@ -135,13 +212,19 @@ async fn get_pessoa_by_id(
 #[derive(Deserialize)]
 struct SearchQuery {
     t: String,
+    mode: Option<String>,
 }
 
 #[get("/pessoas")]
 async fn search_pessoa(
     pool: web::Data<PgPool>,
+    embedder: web::Data<Option<Box<dyn Embedder>>>,
     query: web::Query<SearchQuery>,
-) -> actix_web::Result<impl Responder> {
+) -> actix_web::Result<HttpResponse> {
+    if query.mode.as_deref() == Some("semantic") {
+        return search_pessoa_semantic(pool, embedder, &query.t).await;
+    }
+
     let result = sqlx::query_as::<_, Pessoa>(
         "\
         SELECT id, apelido, nome, nascimento, stack FROM pessoas WHERE search_vector ~ $1 LIMIT 50",
@@ -159,6 +242,47 @@ async fn search_pessoa(
     }
 }
 
+/// Opt-in ranking by cosine similarity against the query's embedding,
+/// for conceptual/typo-tolerant matches the regex search misses.
+async fn search_pessoa_semantic(
+    pool: web::Data<PgPool>,
+    embedder: web::Data<Option<Box<dyn Embedder>>>,
+    term: &str,
+) -> actix_web::Result<HttpResponse> {
+    let embedder = match embedder.get_ref() {
+        Some(embedder) => embedder,
+        None => {
+            return Ok(HttpResponse::UnprocessableEntity()
+                .body("semantic search not configured: set EMBEDDER_HTTP_URL"))
+        }
+    };
+
+    let query_embedding = match embedder.embed(term).await {
+        Ok(vector) => Vector::from(vector),
+        Err(e) => {
+            println!("Failed to compute query embedding: {}", e);
+            return Ok(HttpResponse::InternalServerError().finish());
+        }
+    };
+
+    let result = sqlx::query_as::<_, Pessoa>(
+        "SELECT id, apelido, nome, nascimento, stack, \
+        1 - (embedding <=> $1) AS similarity \
+        FROM pessoas ORDER BY embedding <=> $1 LIMIT 50",
+    )
+    .bind(&query_embedding)
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(matches) => Ok(HttpResponse::Ok().json(matches)),
+        Err(e) => {
+            println!("Failed to execute query: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
 #[get("/contagem-pessoas")]
 async fn count_pessoas(pool: web::Data<PgPool>) -> actix_web::Result<impl Responder> {
     let result = sqlx::query("SELECT COUNT(id) FROM pessoas")
@@ -174,30 +298,115 @@ async fn count_pessoas(pool: web::Data<PgPool>) -> actix_web::Result<impl Respon
     }
 }
 
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let database_url = "postgres://dogfight_user:dogfight_pass@db/dogfight";
+    let database_url = std::env::var("DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://dogfight_user:dogfight_pass@db/dogfight".to_string());
+    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://redis:6379/".to_string());
+    let acquire_timeout_secs: u64 = env_or("DB_ACQUIRE_TIMEOUT_SECS", 120);
+    let workers: usize = env_or("WORKERS", num_cpus::get());
+    let db_max_connections: u32 = env_or("DB_MAX_CONNECTIONS", (num_cpus::get() as u32) * 4);
+    let redis_max_connections: usize = env_or("REDIS_MAX_CONNECTIONS", num_cpus::get() * 4);
+
     let pool = PgPoolOptions::new()
-        .max_connections(50)
-        .acquire_timeout(Duration::from_secs(120))
+        .max_connections(db_max_connections)
+        .acquire_timeout(Duration::from_secs(acquire_timeout_secs))
         .test_before_acquire(false)
         .connect(&database_url)
         .await
         .expect("🔥 Failed to create DB pool");
 
-    let redis_client = Config::from_url("redis://redis:6379/");
+    let mut redis_client = Config::from_url(&redis_url);
+    redis_client.pool = Some(deadpool_redis::PoolConfig::new(redis_max_connections));
     let redis_pool = redis_client.create_pool(Some(Runtime::Tokio1)).unwrap();
 
+    let embedder: web::Data<Option<Box<dyn Embedder>>> =
+        web::Data::new(embedder::build_embedder());
+
+    cache_sync::spawn_cache_listener(pool.clone(), redis_pool.clone(), &database_url).await;
+
+    let ratelimit_limit: u64 = env_or("RATELIMIT_LIMIT", 100);
+    let ratelimit_window_secs: u64 = env_or("RATELIMIT_WINDOW_SECS", 60);
+    let ratelimit_trust_proxy_headers: bool = env_or("RATELIMIT_TRUST_PROXY_HEADERS", false);
+    let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:9999".to_string());
+
+    let metrics_handle = web::Data::new(metrics::init_metrics());
+
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(redis_pool.clone()))
+            .app_data(embedder.clone())
+            .app_data(metrics_handle.clone())
+            .wrap(
+                RateLimit::new(redis_pool.clone(), ratelimit_limit, ratelimit_window_secs)
+                    .skip("/contagem-pessoas")
+                    .skip("/metrics")
+                    .trust_proxy_headers(ratelimit_trust_proxy_headers),
+            )
+            .wrap(metrics::RequestMetrics)
             .service(create_pessoa)
             .service(get_pessoa_by_id)
             .service(search_pessoa)
             .service(count_pessoas)
+            .service(metrics::metrics_endpoint)
     })
-    .bind("0.0.0.0:9999")?
+    .workers(workers)
+    .bind(&bind_addr)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use uuid::Uuid;
+
+    fn test_redis_pool() -> deadpool_redis::Pool {
+        let url = std::env::var("TEST_REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string());
+        Config::from_url(url)
+            .create_pool(Some(Runtime::Tokio1))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_one_concurrent_insert_wins_the_apelido() {
+        let redis_pool = test_redis_pool();
+        let apelido = format!("concurrent-{}", Uuid::new_v4());
+
+        let attempts = (0..10).map(|_| {
+            let redis_pool = redis_pool.clone();
+            let apelido = apelido.clone();
+            tokio::spawn(async move {
+                let mut redis = redis_pool.get().await.unwrap();
+                let id = Uuid::new_v4().to_string();
+                let reserved: i32 = CREATE_PESSOA_SCRIPT
+                    .key(apelido_reservation_key(&apelido))
+                    .key(id)
+                    .arg("{}")
+                    .arg(30)
+                    .invoke_async(&mut redis)
+                    .await
+                    .unwrap();
+                reserved
+            })
+        });
+
+        let results = join_all(attempts).await;
+        let successes: i32 = results.into_iter().map(|r| r.unwrap()).sum();
+
+        assert_eq!(successes, 1);
+
+        let mut redis = redis_pool.get().await.unwrap();
+        let _: () = redis.del(apelido_reservation_key(&apelido)).await.unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{get, Error, HttpResponse, Responder};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+/// Builds the process-wide Prometheus recorder/exporter and installs it as
+/// the global `metrics` recorder, mirroring pict-rs's `init_metrics`.
+pub fn init_metrics() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .install_recorder()
+        .expect("🔥 Failed to install Prometheus recorder")
+}
+
+#[get("/metrics")]
+pub async fn metrics_endpoint(
+    handle: actix_web::web::Data<PrometheusHandle>,
+    pool: actix_web::web::Data<PgPool>,
+) -> impl Responder {
+    metrics::gauge!("db_pool_connections_idle").set(pool.num_idle() as f64);
+    metrics::gauge!("db_pool_connections_in_use")
+        .set((pool.size() as usize).saturating_sub(pool.num_idle()) as f64);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(handle.render())
+}
+
+/// Records `http_requests_total{path,method,status}` and
+/// `http_request_duration_seconds` for every request.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().to_string();
+        let started_at = Instant::now();
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            // Read after routing so this reflects the matched pattern (e.g.
+            // `/pessoas/{id}`) instead of the raw, high-cardinality path.
+            let path = res
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| res.request().path().to_string());
+            let status = res.status().as_u16().to_string();
+
+            metrics::counter!(
+                "http_requests_total",
+                "path" => path.clone(),
+                "method" => method.clone(),
+                "status" => status,
+            )
+            .increment(1);
+            metrics::histogram!(
+                "http_request_duration_seconds",
+                "path" => path,
+                "method" => method,
+            )
+            .record(started_at.elapsed().as_secs_f64());
+
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,199 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpResponse};
+use redis::AsyncCommands;
+
+/// Fixed-window request limiter keyed by client IP, backed by the
+/// shared Redis pool: `INCR` the window's counter, `EXPIRE` it to the window
+/// length on the first hit, and reject once the count exceeds `limit`.
+pub struct RateLimit {
+    redis_pool: deadpool_redis::Pool,
+    limit: u64,
+    window_secs: u64,
+    skip_paths: Rc<Vec<String>>,
+    trust_proxy_headers: bool,
+}
+
+impl RateLimit {
+    pub fn new(redis_pool: deadpool_redis::Pool, limit: u64, window_secs: u64) -> Self {
+        Self {
+            redis_pool,
+            limit,
+            window_secs,
+            skip_paths: Rc::new(Vec::new()),
+            trust_proxy_headers: false,
+        }
+    }
+
+    pub fn skip(mut self, path: impl Into<String>) -> Self {
+        Rc::get_mut(&mut self.skip_paths)
+            .expect("RateLimit::skip must be called before the middleware is built")
+            .push(path.into());
+        self
+    }
+
+    /// When set, the client IP is taken from `X-Forwarded-For` (its
+    /// left-most entry) or `X-Real-IP` instead of the raw TCP peer address.
+    /// Only enable this behind a reverse proxy/load balancer that sets
+    /// those headers itself, since otherwise a client can spoof them to
+    /// dodge the limit.
+    pub fn trust_proxy_headers(mut self, trust: bool) -> Self {
+        self.trust_proxy_headers = trust;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            redis_pool: self.redis_pool.clone(),
+            limit: self.limit,
+            window_secs: self.window_secs,
+            skip_paths: self.skip_paths.clone(),
+            trust_proxy_headers: self.trust_proxy_headers,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    redis_pool: deadpool_redis::Pool,
+    limit: u64,
+    window_secs: u64,
+    skip_paths: Rc<Vec<String>>,
+    trust_proxy_headers: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if self.skip_paths.iter().any(|p| p == req.path()) {
+            let service = Rc::clone(&self.service);
+            return Box::pin(async move {
+                service
+                    .call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body)
+            });
+        }
+
+        let ip = client_ip(&req, self.trust_proxy_headers);
+        let redis_pool = self.redis_pool.clone();
+        let limit = self.limit;
+        let window_secs = self.window_secs;
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let window = now_unix() / window_secs;
+            let key = format!("ratelimit:{}:{}", ip, window);
+
+            let mut redis = redis_pool
+                .get()
+                .await
+                .expect("failed to get redis connection for rate limiting");
+            let count: u64 = redis.incr(&key, 1).await.unwrap_or(1);
+            if count == 1 {
+                let _: Result<(), _> = redis.expire(&key, window_secs as i64).await;
+            }
+
+            let remaining = limit.saturating_sub(count);
+
+            if count > limit {
+                let mut response = HttpResponse::TooManyRequests().finish();
+                let headers = response.headers_mut();
+                headers.insert(
+                    HeaderName::from_static("x-ratelimit-limit"),
+                    HeaderValue::from(limit),
+                );
+                headers.insert(
+                    HeaderName::from_static("x-ratelimit-remaining"),
+                    HeaderValue::from(0),
+                );
+                headers.insert(
+                    HeaderName::from_static("retry-after"),
+                    HeaderValue::from(window_secs),
+                );
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            let headers = res.headers_mut();
+            headers.insert(
+                HeaderName::from_static("x-ratelimit-limit"),
+                HeaderValue::from(limit),
+            );
+            headers.insert(
+                HeaderName::from_static("x-ratelimit-remaining"),
+                HeaderValue::from(remaining),
+            );
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+/// Resolves the IP to key the limiter on. With `trust_proxy_headers` unset
+/// this is always the raw TCP peer, which collapses every client behind a
+/// reverse proxy/load balancer to one address -- enable it only when such a
+/// proxy is guaranteed to set these headers itself.
+fn client_ip(req: &ServiceRequest, trust_proxy_headers: bool) -> String {
+    if trust_proxy_headers {
+        let forwarded_for = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim().to_string());
+        if let Some(ip) = forwarded_for {
+            if !ip.is_empty() {
+                return ip;
+            }
+        }
+
+        let real_ip = req
+            .headers()
+            .get("X-Real-IP")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string());
+        if let Some(ip) = real_ip {
+            if !ip.is_empty() {
+                return ip;
+            }
+        }
+    }
+
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}